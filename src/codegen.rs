@@ -1,4 +1,4 @@
-use crate::core::{Function, Node, NodeKind};
+use crate::core::{AsmLoc, AsmPiece, Function, Node, NodeKind, REGS};
 use crate::utils::error;
 use std::sync::atomic::{AtomicI32, Ordering};
 
@@ -21,19 +21,19 @@ fn pop(str: &str) {
 
 // Compute the absolute address of a given node.
 // It's an error if a given node does not reside in memory.
-fn gen_addr(node: Box<Node>) {
+fn gen_addr(node: &Node) {
     match node.kind {
         NodeKind::Var => {
-            println!("  lea {}(%rbp), %rax", node.var.unwrap().offset);
+            println!("  lea {}(%rbp), %rax", node.var.as_ref().unwrap().offset);
         }
         _ => error("not an lvalue"),
     }
 }
 
-fn gen_expr(node: Box<Node>) {
+fn gen_expr(node: &Node) {
     match node.kind {
         NodeKind::Neg => {
-            gen_expr(node.lhs.unwrap());
+            gen_expr(node.lhs.as_ref().unwrap());
             println!("  neg %rax");
             return;
         }
@@ -41,15 +41,38 @@ fn gen_expr(node: Box<Node>) {
             println!("  mov ${}, %rax", node.num.unwrap());
             return;
         }
+        NodeKind::LabelVal => {
+            println!("  lea {}(%rip), %rax", node.unique_label.as_ref().unwrap());
+            return;
+        }
+        NodeKind::StmtExpr => {
+            gen_stmt(node.lhs.as_ref().unwrap());
+            return;
+        }
+        NodeKind::Trap => {
+            println!("  ud2");
+            return;
+        }
+        NodeKind::FrameAddr | NodeKind::ReturnAddr => {
+            // Walk up the chain of saved frame pointers.
+            println!("  mov %rbp, %rax");
+            for _ in 0..node.num.unwrap() {
+                println!("  mov (%rax), %rax");
+            }
+            if node.kind == NodeKind::ReturnAddr {
+                println!("  mov 8(%rax), %rax");
+            }
+            return;
+        }
         NodeKind::Var => {
             gen_addr(node);
             println!("  mov (%rax), %rax");
             return;
         }
         NodeKind::Assign => {
-            gen_addr(node.lhs.unwrap());
+            gen_addr(node.lhs.as_ref().unwrap());
             push();
-            gen_expr(node.rhs.unwrap());
+            gen_expr(node.rhs.as_ref().unwrap());
             pop("%rdi");
             println!("  mov %rax, (%rdi)");
             return;
@@ -57,9 +80,9 @@ fn gen_expr(node: Box<Node>) {
         _ => {}
     }
 
-    gen_expr(node.rhs.unwrap());
+    gen_expr(node.rhs.as_ref().unwrap());
     push();
-    gen_expr(node.lhs.unwrap());
+    gen_expr(node.lhs.as_ref().unwrap());
     pop("%rdi");
 
     match node.kind {
@@ -85,58 +108,184 @@ fn gen_expr(node: Box<Node>) {
     }
 }
 
-fn gen_stmt(node: Box<Node>) {
+// Callee-saved registers, preserved around an asm statement that
+// uses or clobbers them.
+const CALLEE_SAVED_REGS: [&str; 5] = ["%rbx", "%r12", "%r13", "%r14", "%r15"];
+
+fn asm_operand_text(loc: AsmLoc, modifier: Option<char>) -> String {
+    match loc {
+        AsmLoc::Reg(reg) => {
+            let names = REGS.iter().find(|r| r[0] == reg).unwrap();
+            match modifier {
+                Some('k') => names[1].to_string(),
+                Some('w') => names[2].to_string(),
+                Some('b') => names[3].to_string(),
+                _ => names[0].to_string(),
+            }
+        }
+        AsmLoc::Mem(offset) => format!("{}(%rbp)", offset),
+        AsmLoc::Imm(val) if modifier == Some('c') => val.to_string(),
+        AsmLoc::Imm(val) => format!("${}", val),
+    }
+}
+
+fn gen_asm(node: &Node) {
+    let operands = &node.asm_operands;
+    let clobbers = &node.asm_clobbers;
+    let locs: Vec<AsmLoc> = operands.iter().map(|op| op.loc.unwrap()).collect();
+
+    let saved: Vec<&str> = CALLEE_SAVED_REGS
+        .into_iter()
+        .filter(|r| clobbers.contains(r) || locs.contains(&AsmLoc::Reg(r)))
+        .collect();
+    for reg in &saved {
+        println!("  push {}", reg);
+    }
+
+    // Load the operands that are read by the asm into their registers.
+    let loads: Vec<(&Node, &str)> = operands
+        .iter()
+        .zip(&locs)
+        .filter_map(|(op, loc)| match loc {
+            AsmLoc::Reg(reg) if !op.is_output || op.read_write => Some((&*op.expr, *reg)),
+            _ => None,
+        })
+        .collect();
+    for (expr, _) in &loads {
+        gen_expr(expr);
+        push();
+    }
+    for (_, reg) in loads.iter().rev() {
+        pop(reg);
+    }
+
+    let id = count();
+    let text: String = node
+        .asm_template
+        .iter()
+        .map(|piece| match piece {
+            AsmPiece::Text(text) => text.clone(),
+            AsmPiece::Operand(i, modifier) => asm_operand_text(locs[*i], *modifier),
+            AsmPiece::UniqueId => id.to_string(),
+        })
+        .collect();
+    println!("  {}", text);
+
+    for (op, loc) in operands.iter().zip(&locs) {
+        if let (true, AsmLoc::Reg(reg)) = (op.is_output, loc) {
+            println!(
+                "  mov {}, {}(%rbp)",
+                reg,
+                op.expr.var.as_ref().unwrap().offset
+            );
+        }
+    }
+
+    for reg in saved.iter().rev() {
+        println!("  pop {}", reg);
+    }
+}
+
+fn gen_stmt(node: &Node) {
     match node.kind {
         NodeKind::ExprStmt => {
-            gen_expr(node.lhs.unwrap());
+            gen_expr(node.lhs.as_ref().unwrap());
         }
         NodeKind::Return => {
-            gen_expr(node.lhs.unwrap());
+            gen_expr(node.lhs.as_ref().unwrap());
             println!("  jmp .L.return");
         }
         NodeKind::Block => {
-            let mut stmt_node = node.body;
-            while let Some(mut n) = stmt_node {
-                stmt_node = n.next.take();
+            let mut stmt_node = node.body.as_deref();
+            while let Some(n) = stmt_node {
                 gen_stmt(n);
+                stmt_node = n.next.as_deref();
             }
         }
         NodeKind::If => {
             let c = count();
 
-            gen_expr(node.cond.unwrap());
+            gen_expr(node.cond.as_ref().unwrap());
             println!("  cmp $0, %rax");
             println!("  je .L.else.{}", c);
-            gen_stmt(node.then.unwrap());
+            gen_stmt(node.then.as_ref().unwrap());
             println!("  jmp .L.end.{}", c);
             println!(".L.else.{}:", c);
-            if let Some(els) = node.els {
+            if let Some(els) = &node.els {
                 gen_stmt(els);
             }
             println!(".L.end.{}:", c);
         }
         NodeKind::For => {
             let c = count();
+            let brk_label = node.brk_label.as_ref().unwrap();
 
-            if let Some(init) = node.init {
+            if let Some(init) = &node.init {
                 gen_stmt(init);
             }
             println!(".L.begin.{}:", c);
 
-            if let Some(cond) = node.cond {
+            if let Some(cond) = &node.cond {
                 gen_expr(cond);
                 println!("  cmp $0, %rax");
-                println!("  je .L.end.{}", c);
+                println!("  je {}", brk_label);
             }
 
-            gen_stmt(node.then.unwrap());
+            gen_stmt(node.then.as_ref().unwrap());
+            println!("{}:", node.cont_label.as_ref().unwrap());
 
-            if let Some(inc) = node.inc {
+            if let Some(inc) = &node.inc {
                 gen_expr(inc);
             }
 
             println!("  jmp .L.begin.{}", c);
-            println!(".L.end.{}:", c);
+            println!("{}:", brk_label);
+        }
+        NodeKind::Do => {
+            let c = count();
+
+            println!(".L.begin.{}:", c);
+            gen_stmt(node.then.as_ref().unwrap());
+            println!("{}:", node.cont_label.as_ref().unwrap());
+            gen_expr(node.cond.as_ref().unwrap());
+            println!("  cmp $0, %rax");
+            println!("  jne .L.begin.{}", c);
+            println!("{}:", node.brk_label.as_ref().unwrap());
+        }
+        NodeKind::Switch => {
+            let brk_label = node.brk_label.as_ref().unwrap();
+
+            gen_expr(node.cond.as_ref().unwrap());
+
+            for (val, label) in &node.cases {
+                println!("  mov ${}, %rdi", val);
+                println!("  cmp %rdi, %rax");
+                println!("  je {}", label);
+            }
+
+            match &node.default_label {
+                Some(label) => println!("  jmp {}", label),
+                None => println!("  jmp {}", brk_label),
+            }
+
+            gen_stmt(node.then.as_ref().unwrap());
+            println!("{}:", brk_label);
+        }
+        NodeKind::Case => {
+            println!("{}:", node.unique_label.as_ref().unwrap());
+            gen_stmt(node.lhs.as_ref().unwrap());
+        }
+        NodeKind::Goto => {
+            println!("  jmp {}", node.unique_label.as_ref().unwrap());
+        }
+        NodeKind::Asm => gen_asm(node),
+        NodeKind::GotoExpr => {
+            gen_expr(node.lhs.as_ref().unwrap());
+            println!("  jmp *%rax");
+        }
+        NodeKind::Label => {
+            println!("{}:", node.unique_label.as_ref().unwrap());
+            gen_stmt(node.lhs.as_ref().unwrap());
         }
         _ => error("invalid statement"),
     }
@@ -151,7 +300,7 @@ pub fn codegen(prog: Function) {
     println!("  mov %rsp, %rbp");
     println!("  sub ${}, %rsp\n", prog.stack_size);
 
-    gen_stmt(prog.body);
+    gen_stmt(&prog.body);
 
     assert!(DEPTH.load(Ordering::SeqCst) == 0);
 
@@ -1,8 +1,4 @@
 use crate::utils::{error, error_at};
-use std::cell::RefCell;
-use std::rc::Rc;
-
-type OptRcRef<T> = Option<Rc<RefCell<T>>>;
 
 #[derive(PartialEq)]
 pub enum TokenKind {
@@ -11,7 +7,8 @@ pub enum TokenKind {
     Keywords, // Keywords
     Punct,    // Punctuators
     Num,      // Numeric literals
-    EOF,      // End-of-file markers
+    Str,      // String literals
+    Eof,      // End-of-file markers
 }
 
 /// Token type
@@ -19,6 +16,7 @@ pub struct Token {
     pub kind: TokenKind,
     pub num: Option<i32>,
     pub string: Option<String>,
+    pub str: Option<String>, // String literal contents
     pub next: Option<Box<Token>>,
     pub location: usize,
 }
@@ -41,26 +39,87 @@ pub struct Function {
     pub stack_size: i32,
 }
 
-#[derive(PartialEq)]
+// General-purpose registers by their 64-, 32-, 16- and 8-bit names.
+pub const REGS: [[&str; 4]; 16] = [
+    ["%rax", "%eax", "%ax", "%al"],
+    ["%rbx", "%ebx", "%bx", "%bl"],
+    ["%rcx", "%ecx", "%cx", "%cl"],
+    ["%rdx", "%edx", "%dx", "%dl"],
+    ["%rsi", "%esi", "%si", "%sil"],
+    ["%rdi", "%edi", "%di", "%dil"],
+    ["%rbp", "%ebp", "%bp", "%bpl"],
+    ["%rsp", "%esp", "%sp", "%spl"],
+    ["%r8", "%r8d", "%r8w", "%r8b"],
+    ["%r9", "%r9d", "%r9w", "%r9b"],
+    ["%r10", "%r10d", "%r10w", "%r10b"],
+    ["%r11", "%r11d", "%r11w", "%r11b"],
+    ["%r12", "%r12d", "%r12w", "%r12b"],
+    ["%r13", "%r13d", "%r13w", "%r13b"],
+    ["%r14", "%r14d", "%r14w", "%r14b"],
+    ["%r15", "%r15d", "%r15w", "%r15b"],
+];
+
+// Where an asm operand lives while the asm statement runs
+#[derive(Clone, Copy, PartialEq)]
+pub enum AsmLoc {
+    Reg(&'static str), // 64-bit register name
+    Mem(i32),          // Offset from RBP
+    Imm(i64),
+}
+
+// Operand of an extended asm statement
+pub struct AsmOperand {
+    pub name: Option<String>, // Symbolic name, used as %[name]
+    pub constraint: String,   // Constraint letters without "=", "+" or "&"
+    pub is_output: bool,
+    pub read_write: bool,    // "+" output, also read before the asm
+    pub early_clobber: bool, // "&" output, written before inputs are read
+    pub expr: Box<Node>,     // Lvalue for outputs, value for inputs
+    pub val: Option<i64>,    // Constant value, used by "i" and "n"
+    pub loc: Option<AsmLoc>, // Assigned once all operands are parsed
+    pub location: usize,     // Constraint position, for diagnostics
+}
+
+// Piece of a parsed asm template
+pub enum AsmPiece {
+    Text(String),
+    Operand(usize, Option<char>), // %N or %[name], with a size modifier
+    UniqueId,                     // %=
+}
+
+#[derive(PartialEq, Default)]
 pub enum NodeKind {
-    Empty,    // Empty node
-    Add,      // +
-    Sub,      // -
-    Mul,      // *
-    Div,      // /
-    Neg,      // Unary -
-    Eq,       // ==
-    Ne,       // !=
-    Lt,       // <
-    Le,       // <=
-    Assign,   // =
-    Return,   // "return"
-    If,       // "if"
-    For,      // "for" or "while"
-    Block,    // { ... }
-    ExprStmt, // Expression statement
-    Var,      // Variable
-    Num,      // Integer
+    #[default]
+    Empty, // Empty node
+    Add,        // +
+    Sub,        // -
+    Mul,        // *
+    Div,        // /
+    Neg,        // Unary -
+    Eq,         // ==
+    Ne,         // !=
+    Lt,         // <
+    Le,         // <=
+    Assign,     // =
+    Return,     // "return"
+    If,         // "if"
+    For,        // "for" or "while"
+    Do,         // "do"
+    Switch,     // "switch"
+    Case,       // "case" or "default"
+    Goto,       // "goto", "break" or "continue"
+    GotoExpr,   // "goto" labels-as-values
+    Label,      // Labeled statement
+    LabelVal,   // [GNU] Labels-as-values
+    Block,      // { ... }
+    ExprStmt,   // Expression statement
+    Asm,        // "asm"
+    StmtExpr,   // Statement expression, with the block as lhs
+    Trap,       // "__builtin_trap" or "__builtin_unreachable"
+    FrameAddr,  // "__builtin_frame_address"
+    ReturnAddr, // "__builtin_return_address"
+    Var,        // Variable
+    Num,        // Integer
 }
 
 /// AST node type
@@ -79,9 +138,25 @@ pub struct Node {
     pub init: Option<Box<Node>>,
     pub inc: Option<Box<Node>>,
 
+    // "break" and "continue" labels
+    pub brk_label: Option<String>,
+    pub cont_label: Option<String>,
+
+    // "goto", labeled statement, "case" or "default"
+    pub unique_label: Option<String>,
+
+    // "switch" statement
+    pub cases: Vec<(i64, String)>, // Case values and their labels
+    pub default_label: Option<String>,
+
+    // "asm" statement
+    pub asm_template: Vec<AsmPiece>,
+    pub asm_operands: Vec<AsmOperand>, // Outputs followed by inputs
+    pub asm_clobbers: Vec<&'static str>, // Clobbered registers
+
     pub body: Option<Box<Node>>, // Block
     pub var: Option<Obj>,        // Used if kind == ND_VAR
-    pub num: Option<i32>,        // Used if kind == ND_NUM
+    pub num: Option<i32>,        // Used if kind == ND_NUM or a frame level
 }
 
 impl Token {
@@ -90,6 +165,7 @@ impl Token {
             kind,
             num: None,
             string: None,
+            str: None,
             next: None,
             location,
         })
@@ -159,17 +235,7 @@ impl Node {
         node
     }
 
-    pub fn next(self) -> Box<Node> {
-        self.next.unwrap()
-    }
-
     pub fn next_mut(&mut self) -> &mut Box<Node> {
         self.next.as_mut().unwrap()
     }
 }
-
-impl Default for NodeKind {
-    fn default() -> Self {
-        NodeKind::Empty
-    }
-}
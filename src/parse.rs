@@ -1,12 +1,99 @@
-use crate::core::{Function, Node, NodeKind, Obj, Token, TokenKind};
+use crate::core::{
+    AsmLoc, AsmOperand, AsmPiece, Function, Node, NodeKind, Obj, REGS, Token, TokenKind,
+};
 use crate::utils::error_at;
 use std::sync::Mutex;
+use std::sync::atomic::{AtomicI32, Ordering};
 
 /// All local variable instances created during parsing are
 ///
 /// accumulated to this list.
 static LOCALS_VAR: Mutex<Option<Vec<Obj>>> = Mutex::new(None);
 
+/// Labels that "break" and "continue" jump to. They refer to
+///
+/// the innermost enclosing loop or switch statement.
+static BRK_LABEL: Mutex<Option<String>> = Mutex::new(None);
+static CONT_LABEL: Mutex<Option<String>> = Mutex::new(None);
+
+/// Case labels collected while parsing the body of the innermost
+///
+/// enclosing switch statement.
+#[derive(Default)]
+struct SwitchCases {
+    cases: Vec<(i64, String)>,
+    default_label: Option<String>,
+}
+
+static CURRENT_SWITCH: Mutex<Option<SwitchCases>> = Mutex::new(None);
+
+/// A label defined or referenced in the current function. Labels
+///
+/// have function scope, so "goto" may refer to a label defined later.
+struct Label {
+    name: String,
+    unique_label: String,
+    defined: bool,
+    location: usize, // Location of the first reference
+}
+
+static LABELS: Mutex<Vec<Label>> = Mutex::new(Vec::new());
+
+fn new_unique_name() -> String {
+    static ID: AtomicI32 = AtomicI32::new(0);
+    format!(".L..{}", ID.fetch_add(1, Ordering::SeqCst))
+}
+
+/// Replace the label held by `slot` and return the previous one,
+///
+/// so that it can be restored when leaving a loop or switch.
+fn set_label(slot: &Mutex<Option<String>>, label: Option<String>) -> Option<String> {
+    std::mem::replace(&mut *slot.lock().unwrap(), label)
+}
+
+/// Return the assembly label for a label named `name` in the
+///
+/// current function, creating it on the first reference.
+fn get_label(name: &str, location: usize) -> String {
+    let mut labels = LABELS.lock().unwrap();
+    if let Some(label) = labels.iter().find(|l| l.name == name) {
+        return label.unique_label.clone();
+    }
+
+    let unique_label = new_unique_name();
+    labels.push(Label {
+        name: name.to_string(),
+        unique_label: unique_label.clone(),
+        defined: false,
+        location,
+    });
+    unique_label
+}
+
+fn define_label(name: &str, location: usize) -> String {
+    let unique_label = get_label(name, location);
+    let mut labels = LABELS.lock().unwrap();
+    let label = labels.iter_mut().find(|l| l.name == name).unwrap();
+    if label.defined {
+        error_at(location, &format!("duplicate label '{}'", name));
+    }
+    label.defined = true;
+    unique_label
+}
+
+/// Report labels that are used by "goto" or "&&" but never defined,
+///
+/// and forget the labels of the current function.
+fn resolve_labels() {
+    let labels = std::mem::take(&mut *LABELS.lock().unwrap());
+    if let Some(label) = labels.iter().find(|l| !l.defined) {
+        error_at(
+            label.location,
+            &format!("label '{}' used but not defined", label.name),
+        );
+    }
+}
+
 fn new_locals_var() {
     let mut locals = LOCALS_VAR.lock().unwrap();
     *locals = Some(Vec::new());
@@ -46,10 +133,53 @@ fn align_to(align: i32) -> i32 {
     offset + (align - 1) / align * align
 }
 
+/// Evaluate a given node as a compile-time constant.
+///
+/// Returns None if the node is not a constant expression.
+fn eval(node: &Node) -> Option<i64> {
+    let lhs = || eval(node.lhs.as_ref().unwrap());
+    let rhs = || eval(node.rhs.as_ref().unwrap());
+
+    match node.kind {
+        NodeKind::Add => Some(lhs()?.wrapping_add(rhs()?)),
+        NodeKind::Sub => Some(lhs()?.wrapping_sub(rhs()?)),
+        NodeKind::Mul => Some(lhs()?.wrapping_mul(rhs()?)),
+        NodeKind::Div => lhs()?.checked_div(rhs()?),
+        NodeKind::Neg => Some(lhs()?.wrapping_neg()),
+        NodeKind::Eq => Some((lhs()? == rhs()?) as i64),
+        NodeKind::Ne => Some((lhs()? != rhs()?) as i64),
+        NodeKind::Lt => Some((lhs()? < rhs()?) as i64),
+        NodeKind::Le => Some((lhs()? <= rhs()?) as i64),
+        NodeKind::Num => Some(node.num.unwrap() as i64),
+        _ => None,
+    }
+}
+
+/// const-expr = equality
+fn const_expr(token: Box<Token>) -> (i64, Box<Token>) {
+    let location = token.location;
+    let (node, next_token) = equality(token);
+
+    match eval(&node) {
+        Some(val) => (val, next_token),
+        None => error_at(location, "not a compile-time constant"),
+    }
+}
+
 /// stmt = "return" expr ";"
 ///      | "if" "(" expr ")" stmt ("else" stmt)?
+///      | "switch" "(" expr ")" stmt
+///      | "case" const-expr ":" stmt
+///      | "default" ":" stmt
 ///      | "for" "(" expr-stmt expr? ";" expr? ")" stmt
 ///      | "while" "(" expr ")" stmt
+///      | "do" stmt "while" "(" expr ")" ";"
+///      | "goto" (ident | "*" expr) ";"
+///      | "_Static_assert" "(" const-expr ("," str+)? ")" ";"
+///      | asm-stmt
+///      | "break" ";"
+///      | "continue" ";"
+///      | ident ":" stmt
 ///      | "{" compound-stmt
 ///      | expr-stmt
 fn stmt(token: Box<Token>) -> (Box<Node>, Box<Token>) {
@@ -79,10 +209,93 @@ fn stmt(token: Box<Token>) -> (Box<Node>, Box<Token>) {
         return (node, next_token);
     }
 
+    if token.eq_punct("switch") {
+        let mut node = Node::new(NodeKind::Switch);
+
+        let next_token = token.next().skip("(");
+        let (cond_node, next_token) = expr(next_token);
+        let next_token = next_token.skip(")");
+        node.cond = Some(cond_node);
+
+        let saved_switch = CURRENT_SWITCH
+            .lock()
+            .unwrap()
+            .replace(SwitchCases::default());
+        node.brk_label = Some(new_unique_name());
+        let saved_brk = set_label(&BRK_LABEL, node.brk_label.clone());
+
+        let (then_node, next_token) = stmt(next_token);
+        node.then = Some(then_node);
+
+        set_label(&BRK_LABEL, saved_brk);
+        let switch_cases =
+            std::mem::replace(&mut *CURRENT_SWITCH.lock().unwrap(), saved_switch).unwrap();
+        node.cases = switch_cases.cases;
+        node.default_label = switch_cases.default_label;
+
+        return (node, next_token);
+    }
+
+    if token.eq_punct("case") {
+        if CURRENT_SWITCH.lock().unwrap().is_none() {
+            error_at(token.location, "stray case");
+        }
+
+        let mut node = Node::new(NodeKind::Case);
+        let label = new_unique_name();
+
+        let next_token = token.next();
+        let location = next_token.location;
+        let (val, next_token) = const_expr(next_token);
+        let next_token = next_token.skip(":");
+
+        {
+            let mut current_switch = CURRENT_SWITCH.lock().unwrap();
+            let switch_cases = current_switch.as_mut().unwrap();
+            if switch_cases.cases.iter().any(|(v, _)| *v == val) {
+                error_at(location, &format!("duplicate case value '{}'", val));
+            }
+            switch_cases.cases.push((val, label.clone()));
+        }
+
+        let (body_node, next_token) = stmt(next_token);
+        node.unique_label = Some(label);
+        node.lhs = Some(body_node);
+
+        return (node, next_token);
+    }
+
+    if token.eq_punct("default") {
+        let mut node = Node::new(NodeKind::Case);
+        let label = new_unique_name();
+
+        {
+            let mut current_switch = CURRENT_SWITCH.lock().unwrap();
+            let Some(switch_cases) = current_switch.as_mut() else {
+                error_at(token.location, "stray default");
+            };
+            if switch_cases.default_label.is_some() {
+                error_at(token.location, "multiple default labels in one switch");
+            }
+            switch_cases.default_label = Some(label.clone());
+        }
+
+        let (body_node, next_token) = stmt(token.next().skip(":"));
+        node.unique_label = Some(label);
+        node.lhs = Some(body_node);
+
+        return (node, next_token);
+    }
+
     if token.eq_punct("for") {
         let mut node = Node::new(NodeKind::For);
         let mut post_token = token.next().skip("(");
 
+        node.brk_label = Some(new_unique_name());
+        node.cont_label = Some(new_unique_name());
+        let saved_brk = set_label(&BRK_LABEL, node.brk_label.clone());
+        let saved_cont = set_label(&CONT_LABEL, node.cont_label.clone());
+
         let (init_node, next_token) = expr_stmt(post_token);
         node.init = Some(init_node);
         post_token = next_token;
@@ -104,6 +317,9 @@ fn stmt(token: Box<Token>) -> (Box<Node>, Box<Token>) {
         let (then_node, next_token) = stmt(post_token);
         node.then = Some(then_node);
 
+        set_label(&BRK_LABEL, saved_brk);
+        set_label(&CONT_LABEL, saved_cont);
+
         return (node, next_token);
     }
 
@@ -111,6 +327,11 @@ fn stmt(token: Box<Token>) -> (Box<Node>, Box<Token>) {
         let mut node = Node::new(NodeKind::For);
         let mut post_token = token.next().skip("(");
 
+        node.brk_label = Some(new_unique_name());
+        node.cont_label = Some(new_unique_name());
+        let saved_brk = set_label(&BRK_LABEL, node.brk_label.clone());
+        let saved_cont = set_label(&CONT_LABEL, node.cont_label.clone());
+
         let (cond_node, next_token) = expr(post_token);
         node.cond = Some(cond_node);
         post_token = next_token.skip(")");
@@ -118,6 +339,108 @@ fn stmt(token: Box<Token>) -> (Box<Node>, Box<Token>) {
         let (then_node, next_token) = stmt(post_token);
         node.then = Some(then_node);
 
+        set_label(&BRK_LABEL, saved_brk);
+        set_label(&CONT_LABEL, saved_cont);
+
+        return (node, next_token);
+    }
+
+    if token.eq_punct("do") {
+        let mut node = Node::new(NodeKind::Do);
+
+        node.brk_label = Some(new_unique_name());
+        node.cont_label = Some(new_unique_name());
+        let saved_brk = set_label(&BRK_LABEL, node.brk_label.clone());
+        let saved_cont = set_label(&CONT_LABEL, node.cont_label.clone());
+
+        let (then_node, next_token) = stmt(token.next());
+        node.then = Some(then_node);
+
+        set_label(&BRK_LABEL, saved_brk);
+        set_label(&CONT_LABEL, saved_cont);
+
+        let next_token = next_token.skip("while").skip("(");
+        let (cond_node, next_token) = expr(next_token);
+        node.cond = Some(cond_node);
+
+        return (node, next_token.skip(")").skip(";"));
+    }
+
+    if token.eq_punct("asm") || token.eq_punct("__asm") || token.eq_punct("__asm__") {
+        return asm_stmt(token.next());
+    }
+
+    // The message is optional as in C23. A satisfied assertion
+    // becomes an empty statement.
+    if token.eq_punct("_Static_assert") {
+        let next_token = token.next().skip("(");
+        let location = next_token.location;
+        let (val, mut next_token) = const_expr(next_token);
+
+        let mut message = None;
+        if next_token.eq_punct(",") {
+            let contents;
+            (contents, next_token) = string_literal(next_token.next());
+            message = Some(contents);
+        }
+
+        if val == 0 {
+            match message {
+                Some(message) => error_at(
+                    location,
+                    &format!("static assertion failed: \"{}\"", message),
+                ),
+                None => error_at(location, "static assertion failed"),
+            }
+        }
+
+        return (Node::new(NodeKind::Block), next_token.skip(")").skip(";"));
+    }
+
+    if token.eq_punct("goto") {
+        let next_token = token.next();
+
+        // [GNU] computed goto, e.g. `goto *ptr;`
+        if next_token.eq_punct("*") {
+            let (target_node, next_token) = expr(next_token.next());
+            let node = Node::new_unary(NodeKind::GotoExpr, target_node);
+            return (node, next_token.skip(";"));
+        }
+
+        if next_token.kind != TokenKind::Ident {
+            error_at(next_token.location, "expected a label name");
+        }
+        let name = next_token.string.as_ref().unwrap();
+        let mut node = Node::new(NodeKind::Goto);
+        node.unique_label = Some(get_label(name, next_token.location));
+        return (node, next_token.next().skip(";"));
+    }
+
+    if token.eq_punct("break") {
+        let Some(label) = BRK_LABEL.lock().unwrap().clone() else {
+            error_at(token.location, "stray break");
+        };
+        let mut node = Node::new(NodeKind::Goto);
+        node.unique_label = Some(label);
+        return (node, token.next().skip(";"));
+    }
+
+    if token.eq_punct("continue") {
+        let Some(label) = CONT_LABEL.lock().unwrap().clone() else {
+            error_at(token.location, "stray continue");
+        };
+        let mut node = Node::new(NodeKind::Goto);
+        node.unique_label = Some(label);
+        return (node, token.next().skip(";"));
+    }
+
+    if token.kind == TokenKind::Ident && token.next.as_ref().unwrap().eq_punct(":") {
+        let name = token.string.as_ref().unwrap();
+        let mut node = Node::new(NodeKind::Label);
+        node.unique_label = Some(define_label(name, token.location));
+
+        let (body_node, next_token) = stmt(token.next().next());
+        node.lhs = Some(body_node);
         return (node, next_token);
     }
 
@@ -128,6 +451,413 @@ fn stmt(token: Box<Token>) -> (Box<Node>, Box<Token>) {
     expr_stmt(token)
 }
 
+/// str+, where adjacent string literals are concatenated
+fn string_literal(token: Box<Token>) -> (String, Box<Token>) {
+    if token.kind != TokenKind::Str {
+        error_at(token.location, "expected a string literal");
+    }
+
+    let mut contents = String::new();
+    let mut next_token = token;
+    while next_token.kind == TokenKind::Str {
+        contents.push_str(next_token.str.as_ref().unwrap());
+        next_token = next_token.next();
+    }
+    (contents, next_token)
+}
+
+/// asm-stmt = ("asm" | "__asm" | "__asm__") asm-qualifier* "(" str+
+///            (":" asm-operands? (":" asm-operands? (":" asm-clobbers?)?)?)?
+///            ")" ";"
+/// asm-qualifier = "volatile" | "__volatile__" | "inline" | "__inline__"
+/// asm-clobbers = str ("," str)*
+///
+/// The "asm" keyword has already been consumed. Every asm statement is
+/// emitted as written, so "volatile" and "inline" change nothing.
+fn asm_stmt(token: Box<Token>) -> (Box<Node>, Box<Token>) {
+    let mut next_token = token;
+    while ["volatile", "__volatile__", "inline", "__inline__"]
+        .iter()
+        .any(|q| next_token.eq_punct(q))
+    {
+        next_token = next_token.next();
+    }
+
+    let mut node = Node::new(NodeKind::Asm);
+    next_token = next_token.skip("(");
+    let template_location = next_token.location;
+    let (template, mut next_token) = string_literal(next_token);
+
+    // Basic asm has no operands and its template is emitted verbatim.
+    if !next_token.eq_punct(":") {
+        node.asm_template = vec![AsmPiece::Text(template)];
+        return (node, next_token.skip(")").skip(";"));
+    }
+
+    next_token = asm_operands(next_token.next(), true, &mut node.asm_operands);
+    if next_token.eq_punct(":") {
+        next_token = asm_operands(next_token.next(), false, &mut node.asm_operands);
+    }
+
+    if next_token.eq_punct(":") {
+        next_token = next_token.next();
+        let mut first = true;
+        while !next_token.eq_punct(")") {
+            if !first {
+                next_token = next_token.skip(",");
+            }
+            first = false;
+
+            let location = next_token.location;
+            let clobber;
+            (clobber, next_token) = string_literal(next_token);
+            if let Some(reg) = asm_clobber(&clobber, location) {
+                node.asm_clobbers.push(reg);
+            }
+        }
+    }
+
+    asm_locations(&mut node.asm_operands, &node.asm_clobbers);
+    node.asm_template = asm_template(&template, &node.asm_operands, template_location);
+    (node, next_token.skip(")").skip(";"))
+}
+
+/// Return the 64-bit register named by an asm clobber. Variables always
+///
+/// live in memory and are reloaded on every use, and flags never live
+/// across statements, so "memory" and "cc" clobbers need no action.
+fn asm_clobber(clobber: &str, location: usize) -> Option<&'static str> {
+    if clobber == "memory" || clobber == "cc" {
+        return None;
+    }
+
+    let name = format!("%{}", clobber.trim_start_matches('%'));
+    let Some(regs) = REGS.iter().find(|r| r.contains(&name.as_str())) else {
+        error_at(
+            location,
+            &format!("unknown register name '{}' in asm", clobber),
+        );
+    };
+    if regs[0] == "%rbp" || regs[0] == "%rsp" {
+        error_at(location, &format!("{} cannot be clobbered by asm", regs[0]));
+    }
+    Some(regs[0])
+}
+
+/// Assign a location to each asm operand. An input may share a specific
+///
+/// register with an output unless the output is marked "&" or "+", as
+/// either way both values would need the register at once. "r" operands
+/// always get a register of their own, which also keeps them clear of
+/// every input. Only caller-saved registers are handed out for them, so
+/// that nothing needs to be preserved.
+fn asm_locations(operands: &mut [AsmOperand], clobbers: &[&str]) {
+    let mut used: Vec<&str> = Vec::new();
+    let is_matching = |op: &AsmOperand| op.constraint.chars().all(|c| c.is_ascii_digit());
+
+    // Specific registers go first so that "r" operands avoid them.
+    for i in 0..operands.len() {
+        let op = &operands[i];
+        if is_matching(op) {
+            continue;
+        }
+        let Some(reg) = asm_specific_reg(&op.constraint) else {
+            continue;
+        };
+
+        if clobbers.contains(&reg) {
+            error_at(
+                op.location,
+                &format!("asm operand conflicts with clobbered {}", reg),
+            );
+        }
+
+        let conflict = operands[..i].iter().any(|other| {
+            other.loc == Some(AsmLoc::Reg(reg))
+                && (other.is_output == op.is_output || other.early_clobber || other.read_write)
+        });
+        if conflict {
+            error_at(
+                op.location,
+                &format!("{} is used by more than one asm operand", reg),
+            );
+        }
+
+        used.push(reg);
+        operands[i].loc = Some(AsmLoc::Reg(reg));
+    }
+
+    for op in operands.iter_mut() {
+        if op.loc.is_some() || is_matching(op) {
+            continue;
+        }
+
+        let c = &op.constraint;
+        let loc = if let Some(val) = op.val.filter(|_| c.contains(['i', 'n'])) {
+            AsmLoc::Imm(val)
+        } else if c.contains(['r', 'q', 'g']) {
+            let Some(reg) = [
+                "%rax", "%rcx", "%rdx", "%rsi", "%rdi", "%r8", "%r9", "%r10", "%r11",
+            ]
+            .into_iter()
+            .find(|r| !used.contains(r) && !clobbers.contains(r)) else {
+                error_at(op.location, "ran out of registers for asm operands");
+            };
+            used.push(reg);
+            AsmLoc::Reg(reg)
+        } else if c.contains('m') && op.expr.kind == NodeKind::Var {
+            AsmLoc::Mem(op.expr.var.as_ref().unwrap().offset)
+        } else {
+            error_at(
+                op.location,
+                &format!("impossible constraint \"{}\" in asm", c),
+            );
+        };
+        op.loc = Some(loc);
+    }
+
+    // A matching input takes its output's register once every output
+    // has one. No other input may be loaded into that register as well.
+    for i in 0..operands.len() {
+        let op = &operands[i];
+        if !is_matching(op) {
+            continue;
+        }
+
+        let n: usize = op.constraint.parse().unwrap();
+        let Some(AsmLoc::Reg(reg)) = operands[n].loc else {
+            error_at(
+                op.location,
+                "matching constraint requires a register output",
+            );
+        };
+        if operands
+            .iter()
+            .any(|other| !other.is_output && other.loc == Some(AsmLoc::Reg(reg)))
+        {
+            error_at(
+                op.location,
+                &format!("{} is used by more than one asm operand", reg),
+            );
+        }
+        operands[i].loc = Some(AsmLoc::Reg(reg));
+    }
+}
+
+fn asm_specific_reg(constraint: &str) -> Option<&'static str> {
+    constraint.chars().find_map(|c| match c {
+        'a' => Some("%rax"),
+        'b' => Some("%rbx"),
+        'c' => Some("%rcx"),
+        'd' => Some("%rdx"),
+        'S' => Some("%rsi"),
+        'D' => Some("%rdi"),
+        _ => None,
+    })
+}
+
+/// Split an asm template into text and references to %N, %[name], %%
+///
+/// and %=. An operand reference may carry a b/w/k/q size modifier, or c
+/// for a bare constant.
+fn asm_template(template: &str, operands: &[AsmOperand], location: usize) -> Vec<AsmPiece> {
+    let chars: Vec<char> = template.chars().collect();
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        if chars[i] != '%' {
+            text.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        i += 1;
+
+        match chars.get(i) {
+            Some('%') => {
+                text.push('%');
+                i += 1;
+                continue;
+            }
+            Some('=') => {
+                pieces.push(AsmPiece::Text(std::mem::take(&mut text)));
+                pieces.push(AsmPiece::UniqueId);
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        let mut modifier = None;
+        if let Some(&c) = chars.get(i).filter(|c| "bwkqc".contains(**c)) {
+            modifier = Some(c);
+            i += 1;
+        }
+
+        let index = if chars.get(i) == Some(&'[') {
+            let start = i + 1;
+            while i < chars.len() && chars[i] != ']' {
+                i += 1;
+            }
+            let name: String = chars[start..i].iter().collect();
+            i += 1;
+            operands
+                .iter()
+                .position(|op| op.name.as_deref() == Some(name.as_str()))
+                .unwrap_or_else(|| {
+                    error_at(location, &format!("undefined named operand '{}'", name))
+                })
+        } else {
+            let start = i;
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            if start == i {
+                error_at(location, "invalid '%' in asm template");
+            }
+            let digits: String = chars[start..i].iter().collect();
+            digits
+                .parse()
+                .ok()
+                .filter(|n| *n < operands.len())
+                .unwrap_or_else(|| error_at(location, "operand number out of range in asm"))
+        };
+
+        if modifier == Some('c') && matches!(operands[index].loc, Some(AsmLoc::Reg(_))) {
+            error_at(location, "invalid operand modifier for a register in asm");
+        }
+        pieces.push(AsmPiece::Text(std::mem::take(&mut text)));
+        pieces.push(AsmPiece::Operand(index, modifier));
+    }
+
+    pieces.push(AsmPiece::Text(text));
+    pieces
+}
+
+/// asm-operands = asm-operand ("," asm-operand)*
+/// asm-operand = ("[" ident "]")? str+ "(" expr ")"
+fn asm_operands(token: Box<Token>, is_output: bool, operands: &mut Vec<AsmOperand>) -> Box<Token> {
+    let num_outputs = operands.iter().filter(|op| op.is_output).count();
+    let mut next_token = token;
+    let mut first = true;
+
+    while !next_token.eq_punct(":") && !next_token.eq_punct(")") {
+        if !first {
+            next_token = next_token.skip(",");
+        }
+        first = false;
+
+        let mut name = None;
+        if next_token.eq_punct("[") {
+            next_token = next_token.next();
+            if next_token.kind != TokenKind::Ident {
+                error_at(next_token.location, "expected an operand name");
+            }
+            name = next_token.string.clone();
+            next_token = next_token.next().skip("]");
+        }
+
+        let location = next_token.location;
+        let (constraint, post_token) = string_literal(next_token);
+        let (expr_node, post_token) = expr(post_token.skip("("));
+        next_token = post_token.skip(")");
+
+        // Outputs come first, so they are the leading operands.
+        let operand = new_asm_operand(
+            name,
+            &constraint,
+            expr_node,
+            is_output,
+            &operands[..num_outputs],
+            location,
+        );
+        operands.push(operand);
+    }
+
+    next_token
+}
+
+/// Strip the "=", "+" and "&" modifiers off an asm constraint and
+///
+/// check that the operand expression can satisfy the constraint.
+fn new_asm_operand(
+    name: Option<String>,
+    constraint: &str,
+    expr: Box<Node>,
+    is_output: bool,
+    outputs: &[AsmOperand],
+    location: usize,
+) -> AsmOperand {
+    let read_write = constraint.starts_with('+');
+    if is_output && !read_write && !constraint.starts_with('=') {
+        error_at(location, "output operand constraint lacks '='");
+    }
+    if !is_output && (constraint.contains('=') || constraint.contains('+')) {
+        error_at(location, "input operand constraint contains '=' or '+'");
+    }
+
+    let letters: String = constraint.chars().filter(|c| !"=+&".contains(*c)).collect();
+    if letters.is_empty() {
+        error_at(location, "empty constraint in asm");
+    }
+
+    // A matching constraint places an input in the same location as
+    // the output operand with the given number. A "+" output is
+    // already read as an input, so it cannot be matched again, and an
+    // "&" output is written before inputs are read, so it cannot hold
+    // one.
+    if letters.chars().all(|c| c.is_ascii_digit()) {
+        if is_output {
+            error_at(location, "matching constraint not valid in output operand");
+        }
+        match letters.parse::<usize>().ok().and_then(|n| outputs.get(n)) {
+            None => error_at(
+                location,
+                "matching constraint references invalid operand number",
+            ),
+            Some(output) if output.read_write => error_at(
+                location,
+                "matching constraint references a '+' output operand",
+            ),
+            Some(output) if output.early_clobber => error_at(
+                location,
+                "matching constraint references an '&' output operand",
+            ),
+            Some(_) => {}
+        }
+    } else if let Some(c) = letters.chars().find(|c| !"rqgimnabcdSD".contains(*c)) {
+        error_at(location, &format!("unsupported constraint '{}' in asm", c));
+    }
+
+    if is_output && expr.kind != NodeKind::Var {
+        error_at(location, "invalid lvalue in asm output");
+    }
+    if letters == "m" && expr.kind != NodeKind::Var {
+        error_at(location, "memory input is not directly addressable");
+    }
+
+    let val = eval(&expr);
+    if letters.chars().all(|c| "in".contains(c)) && val.is_none() {
+        error_at(
+            location,
+            "impossible constraint in asm: expected a constant",
+        );
+    }
+
+    AsmOperand {
+        name,
+        constraint: letters,
+        is_output,
+        read_write,
+        early_clobber: constraint.contains('&'),
+        expr,
+        val,
+        loc: None,
+        location,
+    }
+}
+
 /// compound-stmt = stmt* "}"
 fn compound_stmt(token: Box<Token>) -> (Box<Node>, Box<Token>) {
     let mut head = Node::new(NodeKind::Empty);
@@ -135,14 +865,14 @@ fn compound_stmt(token: Box<Token>) -> (Box<Node>, Box<Token>) {
 
     let mut next_token = token;
     let mut expr_node: Box<Node>;
-    while !(&next_token).eq_punct("}") {
+    while !next_token.eq_punct("}") {
         (expr_node, next_token) = stmt(next_token);
         current.next = Some(expr_node);
         current = current.next_mut();
     }
 
     let mut node = Node::new(NodeKind::Block);
-    node.body = Some(head.next());
+    node.body = head.next;
     next_token = next_token.next(); // Skip the closing brace '}'
 
     (node, next_token)
@@ -150,18 +880,18 @@ fn compound_stmt(token: Box<Token>) -> (Box<Node>, Box<Token>) {
 
 /// expr-stmt = expr? ";"
 fn expr_stmt(token: Box<Token>) -> (Box<Node>, Box<Token>) {
-    if (&token).eq_punct(";") {
+    if token.eq_punct(";") {
         return (Node::new(NodeKind::Block), token.next());
     }
 
     let (expr_node, next_token) = expr(token);
     let node = Node::new_unary(NodeKind::ExprStmt, expr_node);
-    return (node, next_token.skip(";"));
+    (node, next_token.skip(";"))
 }
 
 /// expr = assign
 fn expr(token: Box<Token>) -> (Box<Node>, Box<Token>) {
-    return assign(token);
+    assign(token)
 }
 
 fn assign(token: Box<Token>) -> (Box<Node>, Box<Token>) {
@@ -176,7 +906,7 @@ fn assign(token: Box<Token>) -> (Box<Node>, Box<Token>) {
         next_token = token;
     }
 
-    return (left_node, next_token);
+    (left_node, next_token)
 }
 
 // equality = relational ("==" relational | "!=" relational)*
@@ -304,6 +1034,7 @@ fn mul(token: Box<Token>) -> (Box<Node>, Box<Token>) {
 }
 
 // unary = ("+" | "-") unary
+//       | "&&" ident
 //       | primary
 fn unary(token: Box<Token>) -> (Box<Node>, Box<Token>) {
     if token.eq_punct("+") {
@@ -315,23 +1046,135 @@ fn unary(token: Box<Token>) -> (Box<Node>, Box<Token>) {
         return (Node::new_unary(NodeKind::Neg, expr_node), next_token);
     }
 
+    // [GNU] labels-as-values
+    if token.eq_punct("&&") {
+        let next_token = token.next();
+        if next_token.kind != TokenKind::Ident {
+            error_at(next_token.location, "expected a label name");
+        }
+        let name = next_token.string.as_ref().unwrap();
+        let mut node = Node::new(NodeKind::LabelVal);
+        node.unique_label = Some(get_label(name, next_token.location));
+        return (node, next_token.next());
+    }
+
     primary(token)
 }
 
-// primary = "(" expr ")" | ident | num
+/// builtin-call = "__builtin_expect" "(" assign "," assign ")"
+///              | "__builtin_constant_p" "(" assign ")"
+///              | "__builtin_choose_expr" "(" const-expr "," assign "," assign ")"
+///              | ("__builtin_unreachable" | "__builtin_trap") "(" ")"
+///              | "__builtin_frame_address" "(" const-expr ")"
+///              | "__builtin_return_address" "(" const-expr ")"
+///
+/// The builtin name and "(" have already been consumed.
+fn builtin_call(name: &str, location: usize, token: Box<Token>) -> (Box<Node>, Box<Token>) {
+    match name {
+        // The expected value is only a hint for optimisers.
+        "__builtin_expect" => {
+            let (node, next_token) = assign(token);
+            let (_, next_token) = assign(next_token.skip(","));
+            (node, next_token.skip(")"))
+        }
+
+        // The argument is never evaluated at run time.
+        "__builtin_constant_p" => {
+            let (node, next_token) = assign(token);
+            let is_constant = eval(&node).is_some() as i32;
+            (Node::new_num(is_constant), next_token.skip(")"))
+        }
+
+        // Only the chosen expression is evaluated.
+        "__builtin_choose_expr" => {
+            let (cond, next_token) = const_expr(token);
+            let (then_node, next_token) = assign(next_token.skip(","));
+            let (els_node, next_token) = assign(next_token.skip(","));
+            let node = if cond != 0 { then_node } else { els_node };
+            (node, next_token.skip(")"))
+        }
+
+        // Reaching __builtin_unreachable is undefined behavior. Trap
+        // rather than fall into whatever code happens to follow.
+        "__builtin_unreachable" | "__builtin_trap" => (Node::new(NodeKind::Trap), token.skip(")")),
+
+        "__builtin_frame_address" | "__builtin_return_address" => {
+            let arg_location = token.location;
+            let (level, next_token) = const_expr(token);
+            if !(0..=i32::MAX as i64).contains(&level) {
+                error_at(arg_location, &format!("invalid argument to '{}'", name));
+            }
+
+            let mut node = if name == "__builtin_frame_address" {
+                Node::new(NodeKind::FrameAddr)
+            } else {
+                Node::new(NodeKind::ReturnAddr)
+            };
+            node.num = Some(level as i32);
+            (node, next_token.skip(")"))
+        }
+
+        "__builtin_offsetof" | "__builtin_types_compatible_p" => error_at(
+            location,
+            &format!("'{}' needs type names, which are not supported yet", name),
+        ),
+
+        _ => error_at(location, &format!("unknown builtin '{}'", name)),
+    }
+}
+
+// primary = "(" "{" compound-stmt ")"
+//         | "(" expr ")"
+//         | builtin-call
+//         | ident
+//         | num
 fn primary(token: Box<Token>) -> (Box<Node>, Box<Token>) {
     let node: Box<Node>;
-    let mut next_token: Box<Token>;
+    let mut next_token = token;
+
+    // This is a GNU statement expression. Its value is the value
+    // of the last expression statement in the block.
+    if next_token.eq_punct("(") && next_token.next.as_ref().unwrap().eq_punct("{") {
+        let location = next_token.location;
+        let (block_node, next_token) = compound_stmt(next_token.next().next());
+
+        let mut last = block_node.body.as_deref();
+        while let Some(n) = last.and_then(|n| n.next.as_deref()) {
+            last = Some(n);
+        }
+        if !last.is_some_and(|n| n.kind == NodeKind::ExprStmt) {
+            error_at(
+                location,
+                "statement expression returning void is not supported",
+            );
+        }
 
-    if token.eq_punct("(") {
-        next_token = token.next();
+        let node = Node::new_unary(NodeKind::StmtExpr, block_node);
+        return (node, next_token.skip(")"));
+    }
+
+    if next_token.eq_punct("(") {
+        next_token = next_token.next();
         (node, next_token) = expr(next_token);
         next_token = next_token.skip(")");
         return (node, next_token);
     }
 
-    if token.kind == TokenKind::Ident {
-        let name = token.string.clone().unwrap();
+    if next_token.kind == TokenKind::Ident
+        && next_token
+            .string
+            .as_ref()
+            .unwrap()
+            .starts_with("__builtin_")
+        && next_token.next.as_ref().unwrap().eq_punct("(")
+    {
+        let name = next_token.string.clone().unwrap();
+        let location = next_token.location;
+        return builtin_call(&name, location, next_token.next().skip("("));
+    }
+
+    if next_token.kind == TokenKind::Ident {
+        let name = next_token.string.clone().unwrap();
 
         let var = if let Some(exist_var) = find_var(name.as_str()) {
             exist_var
@@ -345,17 +1188,17 @@ fn primary(token: Box<Token>) -> (Box<Node>, Box<Token>) {
         };
 
         node = Node::new_var(var);
-        next_token = token.next();
+        next_token = next_token.next();
         return (node, next_token);
     }
 
-    if token.kind == TokenKind::Num {
-        node = Node::new_num(token.num.unwrap());
-        next_token = token.next();
+    if next_token.kind == TokenKind::Num {
+        node = Node::new_num(next_token.num.unwrap());
+        next_token = next_token.next();
         return (node, next_token);
     }
 
-    error_at(token.location, "expected an expression");
+    error_at(next_token.location, "expected an expression");
 }
 
 pub fn parse(mut token: Box<Token>) -> Function {
@@ -364,6 +1207,7 @@ pub fn parse(mut token: Box<Token>) -> Function {
     new_locals_var();
 
     let body = compound_stmt(token).0;
+    resolve_labels();
 
     let stack_size = align_to(16);
     let _locals = get_locals_var();
@@ -1,22 +1,19 @@
 use crate::core::{Token, TokenKind};
 use crate::utils::{CURRENT_INPUT, error_at};
 
-fn parse_number(s: &str) -> (i32, usize) {
-    let mut chars = s.chars();
-    let mut len = 0;
-
-    while let Some(c) = chars.next() {
-        if c.is_ascii_digit() {
-            len += 1;
-        } else {
-            break;
-        }
+fn parse_number(chars: &[char], pos: usize) -> (i32, usize) {
+    let digits: String = chars[pos..]
+        .iter()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+
+    if digits.is_empty() {
+        return (0, 0);
     }
 
-    if len == 0 {
-        (0, 0)
-    } else {
-        (s[..len].parse().unwrap(), len)
+    match digits.parse() {
+        Ok(num) => (num, digits.len()),
+        Err(_) => error_at(pos, "integer literal too large"),
     }
 }
 
@@ -34,9 +31,9 @@ fn starts_with(input: &[char], pat: &str, pos: usize) -> bool {
 }
 
 // Read a punctuator token from p and returns its length.
-fn read_punct(chars: &Vec<char>, pos: usize) -> (String, usize) {
+fn read_punct(chars: &[char], pos: usize) -> (String, usize) {
     // 双字符运算符
-    for op in ["==", "!=", "<=", ">="] {
+    for op in ["==", "!=", "<=", ">=", "&&"] {
         if starts_with(chars, op, pos) {
             return (op.to_string(), 2);
         }
@@ -49,22 +46,131 @@ fn read_punct(chars: &Vec<char>, pos: usize) -> (String, usize) {
     }
 }
 
+// Read an escape sequence whose backslash is at chars[pos] and
+// return the character along with the length of the sequence.
+fn read_escaped_char(chars: &[char], pos: usize) -> (char, usize) {
+    let mut end = pos + 1;
+
+    // Read an octal number of up to three digits.
+    if ('0'..='7').contains(&chars[end]) {
+        let mut c = 0;
+        while end < chars.len() && end < pos + 4 && ('0'..='7').contains(&chars[end]) {
+            c = c * 8 + chars[end].to_digit(8).unwrap();
+            end += 1;
+        }
+        if c > 0xFF {
+            error_at(pos, "octal escape sequence out of range");
+        }
+        return (char::from(c as u8), end - pos);
+    }
+
+    // Read a hexadecimal number.
+    if chars[end] == 'x' {
+        end += 1;
+        let start = end;
+        let mut c: u32 = 0;
+        while end < chars.len() && chars[end].is_ascii_hexdigit() {
+            c = c
+                .saturating_mul(16)
+                .saturating_add(chars[end].to_digit(16).unwrap());
+            end += 1;
+        }
+        if end == start {
+            error_at(pos, "invalid hex escape sequence");
+        }
+        if c > 0xFF {
+            error_at(pos, "hex escape sequence out of range");
+        }
+        return (char::from(c as u8), end - pos);
+    }
+
+    // Escape sequences are defined using themselves here. E.g.
+    // '\n' is implemented using '\n'. This tautological definition
+    // works because the compiler that compiles our compiler knows
+    // what '\n' actually is.
+    let c = match chars[end] {
+        'a' => '\x07',
+        'b' => '\x08',
+        't' => '\t',
+        'n' => '\n',
+        'v' => '\x0b',
+        'f' => '\x0c',
+        'r' => '\r',
+        'e' => '\x1b', // [GNU] \e for the ASCII escape character
+        c => c,
+    };
+    (c, 2)
+}
+
+// Read a string literal starting at the opening double quote and
+// return its contents and length including both quotes.
+fn read_string_literal(chars: &[char], start: usize) -> (String, usize) {
+    let mut contents = String::new();
+    let mut pos = start + 1;
+
+    loop {
+        if pos >= chars.len() || chars[pos] == '\n' {
+            error_at(start, "unclosed string literal");
+        }
+
+        match chars[pos] {
+            '"' => break,
+            '\\' => {
+                if pos + 1 >= chars.len() {
+                    error_at(start, "unclosed string literal");
+                }
+                let (c, len) = read_escaped_char(chars, pos);
+                contents.push(c);
+                pos += len;
+            }
+            c => {
+                contents.push(c);
+                pos += 1;
+            }
+        }
+    }
+
+    (contents, pos + 1 - start)
+}
+
 /// Returns true if c is valid as the first character of an identifier.
 fn is_ident1(c: char) -> bool {
-    ('a' <= c && c <= 'z') || ('A' <= c && c <= 'Z') || c == '_'
+    c.is_ascii_alphabetic() || c == '_'
 }
 
 /// Returns true if c is valid as the subsequent character of an identifier.
 fn is_ident2(c: char) -> bool {
-    is_ident1(c) || ('0' <= c && c <= '9')
+    is_ident1(c) || c.is_ascii_digit()
 }
 
 fn is_keyword(s: &str) -> bool {
-    ["return", "if", "else", "for", "while"].contains(&s)
+    [
+        "return",
+        "if",
+        "else",
+        "for",
+        "while",
+        "do",
+        "switch",
+        "case",
+        "default",
+        "break",
+        "continue",
+        "goto",
+        "_Static_assert",
+        "asm",
+        "__asm",
+        "__asm__",
+        "volatile",
+        "__volatile__",
+        "inline",
+        "__inline__",
+    ]
+    .contains(&s)
 }
 
 fn convert_keywords(mut token: &mut Box<Token>) {
-    while token.kind != TokenKind::EOF {
+    while token.kind != TokenKind::Eof {
         let token_string = token.string.as_ref();
         if token_string.is_some() && is_keyword(token_string.unwrap()) {
             token.kind = TokenKind::Keywords
@@ -81,7 +187,7 @@ pub fn tokenize(input: &str) -> Box<Token> {
     let chars: Vec<char> = input.chars().collect();
     let mut pos = 0;
 
-    while pos < input.len() {
+    while pos < chars.len() {
         if chars[pos].is_whitespace() {
             pos += 1;
             continue;
@@ -90,12 +196,22 @@ pub fn tokenize(input: &str) -> Box<Token> {
         if chars[pos].is_ascii_digit() {
             current.push(Token::new_token(TokenKind::Num, pos));
             current = current.next_mut();
-            let (num, dis) = parse_number(&input[pos..]);
+            let (num, dis) = parse_number(&chars, pos);
             current.num = Some(num);
             pos += dis;
             continue;
         }
 
+        // String literal
+        if chars[pos] == '"' {
+            let (contents, dis) = read_string_literal(&chars, pos);
+            current.push(Token::new_token(TokenKind::Str, pos));
+            current = current.next_mut();
+            current.str = Some(contents);
+            pos += dis;
+            continue;
+        }
+
         // Identifier or keyword
         if is_ident1(chars[pos]) {
             current.push(Token::new_token(TokenKind::Ident, pos));
@@ -104,7 +220,7 @@ pub fn tokenize(input: &str) -> Box<Token> {
             name.push(chars[pos]);
             pos += 1;
 
-            while pos < input.len() && is_ident2(chars[pos]) {
+            while pos < chars.len() && is_ident2(chars[pos]) {
                 name.push(chars[pos]);
                 pos += 1;
             }
@@ -127,7 +243,7 @@ pub fn tokenize(input: &str) -> Box<Token> {
         error_at(pos, "invalid token");
     }
 
-    current.push(Token::new_token(TokenKind::EOF, pos));
+    current.push(Token::new_token(TokenKind::Eof, pos));
     convert_keywords(head.next_mut());
     head.next()
 }
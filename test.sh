@@ -16,6 +16,23 @@ assert() {
   fi
 }
 
+assert_error() {
+  expected="$1"
+  input="$2"
+
+  if actual=$(./target/debug/rust-chibicc "$input"); then
+    echo "$input => error expected, but it compiled"
+    exit 1
+  fi
+
+  if echo "$actual" | grep -qF -- "$expected"; then
+    echo "$input => $expected"
+  else
+    echo "$input => '$expected' expected, but got: $actual"
+    exit 1
+  fi
+}
+
 assert 0 '{ return 0; }'
 assert 42 '{ return 42; }'
 assert 21 '{ return 5+20-4; }'
@@ -74,4 +91,141 @@ assert 3 '{ for (;;) {return 3;} return 5; }'
 
 assert 10 '{ i=0; while(i<10) { i=i+1; } return i; }'
 
+assert 3 '{ i=0; for(;i<10;i=i+1) { if (i == 3) break; } return i; }'
+assert 4 '{ i=0; while (1) { if (i == 4) break; i=i+1; } return i; }'
+assert 3 '{ i=0; for(;i<10;i=i+1) { for (;;) break; if (i == 3) break; } return i; }'
+assert 4 '{ i=0; while (1) { while(1) break; if (i == 4) break; i=i+1; } return i; }'
+assert 10 '{ i=0; j=0; for (;i<10;i=i+1) { if (i>5) continue; j=j+1; } return i; }'
+assert 6 '{ i=0; j=0; for (;i<10;i=i+1) { if (i>5) continue; j=j+1; } return j; }'
+assert 11 '{ i=0; j=0; while (i<10) { i=i+1; if (i>5) continue; j=j+1; } return i+1; }'
+assert 5 '{ i=0; j=0; while (i<10) { i=i+1; if (i>5) continue; j=j+1; } return j; }'
+
+assert 10 '{ i=0; do { i=i+1; } while (i<10); return i; }'
+assert 1 '{ i=0; do { i=i+1; } while (0); return i; }'
+assert 4 '{ i=0; do { i=i+1; if (i==4) break; } while (i<10); return i; }'
+assert 3 '{ i=0; j=0; do { i=i+1; if (i>3) continue; j=j+1; } while (i<10); return j; }'
+
+assert 5 '{ i=0; switch(0) { case 0:i=5;break; case 1:i=6;break; case 2:i=7;break; } return i; }'
+assert 6 '{ i=0; switch(1) { case 0:i=5;break; case 1:i=6;break; case 2:i=7;break; } return i; }'
+assert 7 '{ i=0; switch(2) { case 0:i=5;break; case 1:i=6;break; case 2:i=7;break; } return i; }'
+assert 0 '{ i=0; switch(3) { case 0:i=5;break; case 1:i=6;break; case 2:i=7;break; } return i; }'
+assert 5 '{ i=0; switch(0) { case 0:i=5;break; default:i=7; } return i; }'
+assert 7 '{ i=0; switch(1) { case 0:i=5;break; default:i=7; } return i; }'
+assert 7 '{ i=0; switch(1) { default:i=7;break; case 0:i=5; } return i; }'
+assert 2 '{ i=0; switch(0) { case 0:i=i+1; case 1:i=i+1; } return i; }'
+assert 11 '{ i=0; switch(5-2*2) { case 2-1:i=11;break; case -1:i=12; } return i; }'
+assert 4 '{ i=0; j=0; for (;i<5;i=i+1) { switch(i) { case 1: continue; case 3: break; } j=j+1; } return j; }'
+assert 0 '{ i=0; switch(1) { case 0: if (1) { case 1: i=0; } } return i; }'
+
+assert_error "duplicate case value '1'" '{ switch(1) { case 1: case 2-1: ; } }'
+assert_error 'multiple default labels in one switch' '{ switch(1) { default: ; default: ; } }'
+assert_error 'not a compile-time constant' '{ switch(1) { case a: ; } }'
+assert_error 'stray break' '{ break; }'
+assert_error 'stray continue' '{ continue; }'
+assert_error 'stray continue' '{ switch(1) { continue; } }'
+assert_error 'stray case' '{ case 1: ; }'
+assert_error 'stray default' '{ default: ; }'
+assert_error 'stray break' '{ for (;;) {} break; }'
+assert 1 '{ switch(2147483647) { case 2147483647: return 1; } return 0; }'
+assert_error 'integer literal too large' '{ switch(1) { case 2147483648: ; } }'
+assert_error 'integer literal too large' '{ return 99999999999999999999999; }'
+
+assert 3 '{ i=0; goto a; a: i=i+1; b: i=i+1; c: i=i+1; return i; }'
+assert 2 '{ i=0; goto e; d: i=i+1; e: i=i+1; f: i=i+1; return i; }'
+assert 1 '{ i=0; goto i; g: i=i+1; h: i=i+1; i: i=i+1; return i; }'
+assert 10 '{ i=0; loop: if (i<10) { i=i+1; goto loop; } return i; }'
+assert 7 '{ i=0; { goto out; } i=5; out: return i+7; }'
+assert 2 '{ p=&&b; goto *p; a: return 1; b: return 2; }'
+assert 6 '{ i=0; j=0; p=&&inc; loop: if (i==3) goto *&&done; goto *p; inc: i=i+1; j=j+2; goto loop; done: return j; }'
+assert 1 '{ return &&a != &&b; a: 1; b: ; }'
+assert 1 '{ return &&a == &&b; a: b: ; }'
+
+assert_error "label 'x' used but not defined" '{ goto x; }'
+assert_error "label 'y' used but not defined" '{ x: goto y; }'
+assert_error "duplicate label 'a'" '{ a: a: ; }'
+assert_error "duplicate label 'a'" '{ a: ; { a: ; } }'
+assert_error "label 'nope' used but not defined" '{ return &&nope; }'
+assert_error 'expected a label name' '{ goto 1; }'
+
+assert 3 '{ _Static_assert(1+2*3==7, "arithmetic"); return 3; }'
+assert 4 '{ _Static_assert(-(8-2)/3 < 0); { _Static_assert(1, "nested\n"); } return 4; }'
+assert 5 '{ switch (5) { case 5: _Static_assert(5 == 2+3, "case"); return 5; } return 0; }'
+assert 3 '{ _Static_assert(1, "é"); return 3; }'
+assert 42 '{ _Static_assert(1, "héllo"); x=40; return x+2; }'
+assert_error 'invalid token' '{ é=1; }'
+assert 1 '{ _Static_assert(1, "a" "b" "c"); return 1; }'
+assert_error 'static assertion failed: "hdr layout"' '{ _Static_assert(2*8 == 15, "hdr layout"); }'
+assert_error 'static assertion failed: "a b"' '{ _Static_assert(0, "a " "b"); }'
+assert_error 'static assertion failed' '{ _Static_assert(1-1); }'
+assert_error 'static assertion failed: "S4Ag"' '{ _Static_assert(0, "\1234\x41g"); }'
+assert_error 'static assertion failed: "Zb"' '{ _Static_assert(0, "\132\x62"); }'
+assert_error 'expected a string literal' '{ _Static_assert(1, 2); }'
+assert_error 'hex escape sequence out of range' '{ _Static_assert(1, "\x100"); }'
+assert_error 'octal escape sequence out of range' '{ _Static_assert(1, "\777"); }'
+assert_error 'invalid hex escape sequence' '{ _Static_assert(1, "\xg"); }'
+assert_error "expected a ';'" '{ _Static_assert(1, "ü") return 1; }'
+
+assert 0 '{ {} return 0; }'
+assert 0 '{ return ({ 0; }); }'
+assert 2 '{ return ({ 0; 1; 2; }); }'
+assert 1 '{ ({ 0; return 1; 2; }); return 3; }'
+assert 6 '{ return ({ 1; }) + ({ 2; }) + ({ 3; }); }'
+assert 9 '{ x=3; return ({ t=x; t*t; }); }'
+assert 3 '{ i=({ j=0; while (j<3) j=j+1; j; }); return i; }'
+assert 4 '{ return ({ i=0; for (;;) { i=i+1; if (i==4) break; } i; }); }'
+
+assert 3 '{ asm("nop"); __asm__ volatile ("nop\n\tnop"); return 3; }'
+assert 1 '{ asm volatile("rdtsc" : "=a"(lo), "=d"(hi)); return lo+hi != 0; }'
+assert 1 '{ asm volatile("cpuid" : "=a"(a), "=b"(b), "=c"(c), "=d"(d) : "a"(0)); return a > 0; }'
+assert 7 '{ x=3; asm("add $4, %0" : "+r"(x)); return x; }'
+assert 42 '{ asm("mov %1, %0" : "=r"(x) : "i"(40+2)); return x; }'
+assert 15 '{ y=5; asm("lea (%1,%1,2), %0" : "=r"(x) : "r"(y)); return x; }'
+assert 12 '{ y=6; asm("add %0, %0" : "=r"(x) : "0"(y)); return x; }'
+assert 7 '{ a=10; b=3; asm("sub %[b], %[a]" : [a] "+r"(a) : [b] "r"(b)); return a; }'
+assert 10 '{ x=1; asm("addq $9, %0" : "+m"(x)); return x; }'
+assert 5 '{ x=0; asm("mov $5, %%rax; mov %%rax, %0" : "=r"(x) : : "rax", "memory", "cc"); return x; }'
+assert 7 '{ x=-1; asm("movl $7, %k0" : "+r"(x)); return x; }'
+assert 9 '{ y=4; asm("mov %1, %0\n\t" "add $5, %0" : "=&r"(x) : "r"(y)); return x; }'
+assert 8 '{ x=8; asm("jmp .Lskip%=\n.Lskip%=:" : : ); asm("jmp .Lskip%=\n.Lskip%=:" : : : ); return x; }'
+assert 6 '{ x=1; y=5; asm("add %2, %0" : "=D"(z) : "0"(x), "S"(y) : "rbx", "r12"); return z; }'
+assert 3 '{ x=1; y=2; asm("add %1, %0" : "+a"(x) : "c"(y)); return x; }'
+
+assert_error 'output operand constraint lacks' '{ asm("" : "r"(x)); }'
+assert_error 'invalid lvalue in asm output' '{ asm("" : "=r"(1)); }'
+assert_error 'expected a constant' '{ asm("" : : "i"(x)); }'
+assert_error 'unsupported constraint' '{ asm("" : : "x"(x)); }'
+assert_error 'matching constraint references invalid operand number' '{ asm("" : "=r"(x) : "1"(x)); }'
+assert_error 'matching constraint references invalid operand number' '{ asm("" : "=r"(x) : "99999999999999999999"(1)); }'
+assert_error "matching constraint references a '+' output operand" '{ asm("add %1, %0" : "+r"(x) : "0"(y)); }'
+assert_error "matching constraint references an '&' output operand" '{ asm("" : "=&r"(x) : "0"(y)); }'
+assert_error '%rax is used by more than one asm operand' '{ x=0; y=7; z=9; asm("" : "=a"(x) : "0"(y), "a"(z)); return x; }'
+assert_error '%rax is used by more than one asm operand' '{ x=0; y=7; z=9; asm("" : "=a"(x) : "a"(z), "0"(y)); return x; }'
+assert_error '%rax is used by more than one asm operand' '{ y=7; z=9; asm("" : "=r"(x) : "0"(y), "0"(z)); }'
+assert_error '%rax is used by more than one asm operand' '{ x=1; y=2; asm("" : "+a"(x) : "a"(y)); }'
+assert_error '%rax is used by more than one asm operand' '{ asm("" : "=&a"(x) : "a"(y)); }'
+assert_error '%rax is used by more than one asm operand' '{ asm("" : "=a"(x), "=a"(y)); }'
+assert_error 'asm operand conflicts with clobbered %rax' '{ asm("" : "=a"(x) : : "eax"); }'
+assert_error 'operand number out of range in asm' '{ asm("%3" : "=r"(x)); }'
+assert_error 'operand number out of range in asm' '{ asm("%99999999999999999999999" ::); }'
+assert_error "undefined named operand 'q'" '{ asm("%[q]" : "=r"(x)); }'
+assert_error "unknown register name 'foo' in asm" '{ asm("" : : : "foo"); }'
+assert_error '%rbp cannot be clobbered by asm' '{ asm("" : : : "ebp"); }'
+assert_error 'ran out of registers for asm operands' '{ x=1; asm("" : "+r"(x) : : "rax","rcx","rdx","rsi","rdi","r8","r9","r10","r11"); return x; }'
+assert_error "invalid '%' in asm template" '{ asm("%z0" : "=r"(x)); }'
+assert_error 'invalid operand modifier for a register in asm' '{ asm("%c0" : "=r"(x)); }'
+
+assert 3 '{ return __builtin_expect(3, 1); }'
+assert 1 '{ x=5; if (__builtin_expect(x == 5, 0)) return 1; return 0; }'
+assert 1 '{ return __builtin_constant_p(3*4-1); }'
+assert 0 '{ x=1; return __builtin_constant_p(x); }'
+assert 1 '{ x=1; __builtin_constant_p(x=5); return x; }'
+assert 7 '{ return __builtin_choose_expr(1, 7, 8); }'
+assert 4 '{ x=0; __builtin_choose_expr(2<1, x=3, x=4); return x; }'
+assert 9 '{ switch (3) { case __builtin_choose_expr(2>1, 3, 4): return 9; } return 0; }'
+assert 1 '{ return __builtin_frame_address(0) != 0; }'
+assert 1 '{ asm("mov %%rbp, %0" : "=r"(x)); return x == __builtin_frame_address(0); }'
+assert 1 '{ asm("mov 8(%%rbp), %0" : "=r"(x)); return x == __builtin_return_address(0); }'
+assert 5 '{ if (0) __builtin_unreachable(); return 5; }'
+assert 6 '{ if (0) __builtin_trap(); return 6; }'
+
 echo OK